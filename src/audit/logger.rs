@@ -0,0 +1,7 @@
+/// Writes a line to the gateway's audit log.
+///
+/// Synchronous so it can be called from contexts (error branches, `Drop`
+/// impls) that don't have access to an async runtime.
+pub fn log_sync(message: &str) {
+    println!("[acceptevm] {}", message);
+}
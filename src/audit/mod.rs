@@ -0,0 +1,3 @@
+pub mod logger;
+
+pub use logger::log_sync;
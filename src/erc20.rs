@@ -0,0 +1,39 @@
+use thiserror::Error;
+use web3::types::{Address, BlockNumber, U256};
+
+use crate::gateway::provider::{ProviderError, ProviderPool};
+
+#[derive(Error, Debug)]
+pub enum TokenBalanceError {
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+    #[error(transparent)]
+    Provider(#[from] ProviderError),
+}
+
+/// A handle to an ERC20 token contract, used to query balances through a
+/// quorum-backed `ProviderPool`.
+#[derive(Debug, Clone, Copy)]
+pub struct ERC20Token {
+    address: Address,
+}
+
+impl ERC20Token {
+    pub fn new(address: Address) -> Self {
+        Self { address }
+    }
+
+    /// Retrieves the token balance of `address` as of `block`, agreed on by
+    /// quorum.
+    pub async fn get_balance(
+        &self,
+        providers: &mut ProviderPool,
+        address: String,
+        block: Option<BlockNumber>,
+    ) -> Result<U256, TokenBalanceError> {
+        let owner: Address = address
+            .parse()
+            .map_err(|_| TokenBalanceError::InvalidAddress(address))?;
+        Ok(providers.token_balance(self.address, owner, block).await?)
+    }
+}
@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use web3::types::U256;
+
+use crate::{
+    common::DatabaseError,
+    gateway::db::{self, Transaction},
+};
+
+const COUNTERS_KEY: &str = "counters";
+
+/// Aggregate lifecycle counters for invoices handled by a gateway, kept in
+/// their own tree so operators can query gateway health without iterating
+/// the whole invoice tree.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Counters {
+    pub open: u64,
+    pub paid: u64,
+    pub expired: u64,
+    pub cumulative_value: U256,
+}
+
+/// Applies `transition` to the counters read through an already-open `tx`,
+/// so callers can fold a counter update into a larger transaction (e.g. the
+/// invoice write it accounts for) instead of committing it on its own.
+fn apply(tx: &Transaction, transition: impl Fn(&mut Counters)) -> Result<(), DatabaseError> {
+    let mut counters = tx.get::<Counters>(COUNTERS_KEY)?.unwrap_or_default();
+    transition(&mut counters);
+    tx.set(COUNTERS_KEY, counters)
+}
+
+/// Records a newly created open invoice, as part of `tx`.
+pub fn apply_opened(tx: &Transaction) -> Result<(), DatabaseError> {
+    apply(tx, |counters| counters.open += 1)
+}
+
+/// Moves an invoice from open to paid, adding `amount` to the cumulative
+/// settled total, as part of `tx`.
+pub fn apply_paid(tx: &Transaction, amount: U256) -> Result<(), DatabaseError> {
+    apply(tx, |counters| {
+        counters.open = counters.open.saturating_sub(1);
+        counters.paid += 1;
+        counters.cumulative_value += amount;
+    })
+}
+
+/// Moves an invoice from open to expired, as part of `tx`.
+pub fn apply_expired(tx: &Transaction) -> Result<(), DatabaseError> {
+    apply(tx, |counters| {
+        counters.open = counters.open.saturating_sub(1);
+        counters.expired += 1;
+    })
+}
+
+/// Reads the current counters without mutating them.
+pub fn read(tree: &Tree) -> Counters {
+    tree.get(COUNTERS_KEY)
+        .ok()
+        .flatten()
+        .and_then(|bytes| bincode::deserialize(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Rescans `invoices` and repairs the `open` counter to match what is
+/// actually stored there, so an unclean shutdown between an invoice write
+/// and its counter update can't leave the two permanently out of sync.
+/// `paid`, `expired` and `cumulative_value` are not recoverable this way
+/// once an invoice has been deleted, so they are left untouched.
+pub fn repair_counters(invoices: &Tree, counters_tree: &Tree) -> Result<(), DatabaseError> {
+    let open = invoices.len() as u64;
+    db::transact(counters_tree, |tx| {
+        apply(tx, |counters| counters.open = open)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_tree() -> Tree {
+        sled::Config::new()
+            .temporary(true)
+            .open()
+            .unwrap()
+            .open_tree("counters")
+            .unwrap()
+    }
+
+    #[test]
+    fn apply_opened_increments_open() {
+        let tree = temporary_tree();
+        db::transact(&tree, apply_opened).unwrap();
+        db::transact(&tree, apply_opened).unwrap();
+        assert_eq!(read(&tree).open, 2);
+    }
+
+    #[test]
+    fn apply_paid_moves_open_to_paid_and_accumulates_value() {
+        let tree = temporary_tree();
+        db::transact(&tree, apply_opened).unwrap();
+        db::transact(&tree, |tx| apply_paid(tx, U256::from(100))).unwrap();
+
+        let counters = read(&tree);
+        assert_eq!(counters.open, 0);
+        assert_eq!(counters.paid, 1);
+        assert_eq!(counters.cumulative_value, U256::from(100));
+    }
+
+    #[test]
+    fn apply_expired_moves_open_to_expired() {
+        let tree = temporary_tree();
+        db::transact(&tree, apply_opened).unwrap();
+        db::transact(&tree, apply_expired).unwrap();
+
+        let counters = read(&tree);
+        assert_eq!(counters.open, 0);
+        assert_eq!(counters.expired, 1);
+    }
+
+    #[test]
+    fn apply_paid_on_empty_open_saturates_at_zero() {
+        let tree = temporary_tree();
+        db::transact(&tree, |tx| apply_paid(tx, U256::from(1))).unwrap();
+        assert_eq!(read(&tree).open, 0);
+    }
+
+    #[test]
+    fn repair_counters_rebuilds_open_from_invoice_tree() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let invoices = db.open_tree("invoices").unwrap();
+        let counters_tree = db.open_tree("counters").unwrap();
+
+        invoices.insert("invoice:1", b"a".as_slice()).unwrap();
+        invoices.insert("invoice:2", b"b".as_slice()).unwrap();
+        db::transact(&counters_tree, apply_opened).unwrap();
+
+        repair_counters(&invoices, &counters_tree).unwrap();
+
+        assert_eq!(read(&counters_tree).open, 2);
+    }
+}
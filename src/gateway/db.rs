@@ -0,0 +1,394 @@
+use sled::{
+    transaction::{
+        ConflictableTransactionError, TransactionError, Transactional, TransactionalTree,
+    },
+    Tree,
+};
+
+use crate::{audit::log_sync, common::DatabaseError, types::Serializable};
+
+/// Retrieve a value by key from a tree.
+async fn get_from_tree(db: &Tree, key: &str) -> Result<Vec<u8>, DatabaseError> {
+    match db.get(key) {
+        Ok(result) => match result {
+            Some(value) => Ok(value.to_vec()),
+            None => Err(DatabaseError::NotFound),
+        },
+        Err(_error) => Err(DatabaseError::Get),
+    }
+}
+/// Retrieve all key,value pairs from a specified tree
+async fn get_all_from_tree(db: &Tree) -> Result<Vec<(Vec<u8>, Vec<u8>)>, DatabaseError> {
+    let mut all = Vec::new();
+    for el in db.iter() {
+        match el {
+            Ok(value) => {
+                let el_bin_key = value.0.to_vec();
+                let el_bin_value = value.1.to_vec();
+                all.push((el_bin_key, el_bin_value));
+            }
+            Err(error) => {
+                log_sync(&format!("Db Interaction Error: {}", error));
+                return Err(DatabaseError::Get);
+            }
+        }
+    }
+    Ok(all)
+}
+
+/// Retrieve the last added item to the tree
+async fn get_last_from_tree(db: &Tree) -> Result<(Vec<u8>, Vec<u8>), DatabaseError> {
+    match db.last() {
+        Ok(value) => match value {
+            Some(tuple) => {
+                let el_bin_key = tuple.0.to_vec();
+                let el_bin_value = tuple.1.to_vec();
+                Ok((el_bin_key, el_bin_value))
+            }
+            None => Err(DatabaseError::NotFound),
+        },
+        Err(error) => {
+            log_sync(&format!("Db Interaction Error: {}", error));
+            Err(DatabaseError::Get)
+        }
+    }
+}
+
+/// Wrapper for retrieving the last added item to the tree
+pub async fn get_last<T: Serializable>(tree: &sled::Tree) -> Result<(String, T), DatabaseError> {
+    let binary_data = get_last_from_tree(tree).await?;
+    let key = String::from_utf8(binary_data.0).map_err(|error| {
+        log_sync(&format!("Db Interaction Error: {}", error));
+        DatabaseError::Deserialize
+    })?;
+
+    let value = T::from_bin(binary_data.1).map_err(|error| {
+        log_sync(&format!("Db Interaction Error: {}", error));
+        DatabaseError::Deserialize
+    })?;
+    Ok((key, value))
+}
+
+/// Wrapper for retrieving all key value pairs from a tree
+pub async fn get_all<T: Serializable>(
+    tree: &sled::Tree,
+) -> Result<Vec<(String, T)>, DatabaseError> {
+    let binary_data = get_all_from_tree(tree).await?;
+    let mut all = Vec::new();
+    for (binary_key, binary_value) in binary_data {
+        let key = String::from_utf8(binary_key.to_vec()).map_err(|error| {
+            log_sync(&format!("Db Interaction Error: {}", error));
+            DatabaseError::Deserialize
+        })?;
+
+        let value = T::from_bin(binary_value).map_err(|error| {
+            log_sync(&format!("Db Interaction Error: {}", error));
+            DatabaseError::Deserialize
+        })?;
+
+        all.push((key, value));
+    }
+    Ok(all)
+}
+
+/// Wrapper for retrieving a value from a tree
+pub async fn get<T: Serializable>(tree: &Tree, key: &str) -> Result<T, DatabaseError> {
+    let binary_data = get_from_tree(tree, key).await?;
+    T::from_bin(binary_data).map_err(|error| {
+        log_sync(&format!("Db Interaction Error: {}", error));
+        DatabaseError::Deserialize
+    })
+}
+
+/// Sets a value to a tree
+async fn set_to_tree(db: &Tree, key: &str, bin: Vec<u8>) -> Result<(), DatabaseError> {
+    match db.insert(key, bin) {
+        Ok(_) => Ok(()),
+        Err(error) => {
+            log_sync(&format!("Db Interaction Error: {}", error));
+            Err(DatabaseError::Set)
+        }
+    }
+}
+
+/// Wrapper for setting a value to a tree
+pub async fn set<T: Serializable>(tree: &Tree, key: &str, data: T) -> Result<(), DatabaseError> {
+    let binary_data = T::to_bin(&data).map_err(|error| {
+        log_sync(&format!("Db Interaction Error: {}", error));
+        DatabaseError::Serialize
+    })?;
+    set_to_tree(tree, key, binary_data)
+        .await
+        .map_err(|_| DatabaseError::Communicate)?;
+    Ok(())
+}
+
+/// Used to delete from a tree
+pub async fn delete(tree: &Tree, key: &str) -> Result<(), DatabaseError> {
+    match tree.remove(key) {
+        Ok(result) => match result {
+            Some(_deleted_value) => Ok(()),
+            None => Err(DatabaseError::NotFound),
+        },
+        Err(error) => {
+            log_sync(&format!("Db Interaction Error: {}", error));
+            Err(DatabaseError::NoDelete)
+        }
+    }
+}
+
+/// Sets every entry in `tree` as a single atomic batch: either all of them
+/// land, or none do. A crash mid-write can never leave only some of the
+/// entries applied.
+pub async fn set_many<T: Serializable>(
+    tree: &Tree,
+    entries: Vec<(String, T)>,
+) -> Result<(), DatabaseError> {
+    let mut batch = sled::Batch::default();
+    for (key, value) in entries {
+        let binary_data = value.to_bin().map_err(|error| {
+            log_sync(&format!("Db Interaction Error: {}", error));
+            DatabaseError::Serialize
+        })?;
+        batch.insert(key.as_bytes(), binary_data);
+    }
+    tree.apply_batch(batch).map_err(|error| {
+        log_sync(&format!("Db Interaction Error: {}", error));
+        DatabaseError::Set
+    })
+}
+
+/// Deletes every key in `keys` from `tree` as a single atomic batch.
+pub async fn delete_many(tree: &Tree, keys: &[&str]) -> Result<(), DatabaseError> {
+    let mut batch = sled::Batch::default();
+    for key in keys {
+        batch.remove(key.as_bytes());
+    }
+    tree.apply_batch(batch).map_err(|error| {
+        log_sync(&format!("Db Interaction Error: {}", error));
+        DatabaseError::NoDelete
+    })
+}
+
+/// A handle into an in-progress `transact` closure, used to stage typed
+/// reads and writes that all commit together or not at all.
+pub struct Transaction<'a> {
+    tree: &'a TransactionalTree,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn get<T: Serializable>(&self, key: &str) -> Result<Option<T>, DatabaseError> {
+        let binary_data = self
+            .tree
+            .get(key.as_bytes())
+            .map_err(|_| DatabaseError::Get)?;
+        binary_data
+            .map(|bin| T::from_bin(bin.to_vec()))
+            .transpose()
+    }
+
+    pub fn set<T: Serializable>(&self, key: &str, value: T) -> Result<(), DatabaseError> {
+        let binary_data = value.to_bin()?;
+        self.tree
+            .insert(key.as_bytes(), binary_data)
+            .map_err(|_| DatabaseError::Set)?;
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), DatabaseError> {
+        self.tree
+            .remove(key.as_bytes())
+            .map_err(|_| DatabaseError::NoDelete)?;
+        Ok(())
+    }
+}
+
+/// Runs `f` against `tree` inside a single sled transaction: every read/write
+/// `f` stages through the provided `Transaction` handle commits atomically,
+/// or none do if `f` returns an error.
+pub fn transact<F>(tree: &Tree, f: F) -> Result<(), DatabaseError>
+where
+    F: Fn(&Transaction) -> Result<(), DatabaseError>,
+{
+    tree.transaction(|tx| {
+        let handle = Transaction { tree: tx };
+        f(&handle).map_err(ConflictableTransactionError::Abort)
+    })
+    .map_err(|error: TransactionError<DatabaseError>| match error {
+        TransactionError::Abort(db_error) => db_error,
+        TransactionError::Storage(error) => {
+            log_sync(&format!("Db Interaction Error: {}", error));
+            DatabaseError::Communicate
+        }
+    })
+}
+
+/// Runs `f` against `tree_a` and `tree_b` inside a single sled transaction
+/// spanning both trees: every read/write staged through the two `Transaction`
+/// handles commits atomically across both, or neither does. Used to keep an
+/// invoice write and the aggregate counters it affects from ever drifting
+/// out of sync, even if the process crashes mid-write.
+pub fn transact2<F>(tree_a: &Tree, tree_b: &Tree, f: F) -> Result<(), DatabaseError>
+where
+    F: Fn(&Transaction, &Transaction) -> Result<(), DatabaseError>,
+{
+    (tree_a, tree_b)
+        .transaction(|(tx_a, tx_b)| {
+            let handle_a = Transaction { tree: tx_a };
+            let handle_b = Transaction { tree: tx_b };
+            f(&handle_a, &handle_b).map_err(ConflictableTransactionError::Abort)
+        })
+        .map_err(|error: TransactionError<DatabaseError>| match error {
+            TransactionError::Abort(db_error) => db_error,
+            TransactionError::Storage(error) => {
+                log_sync(&format!("Db Interaction Error: {}", error));
+                DatabaseError::Communicate
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_tree() -> Tree {
+        sled::Config::new()
+            .temporary(true)
+            .open()
+            .unwrap()
+            .open_tree("test")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn set_then_get_round_trips() {
+        let tree = temporary_tree();
+        set(&tree, "key", 42u64).await.unwrap();
+        assert_eq!(get::<u64>(&tree, "key").await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn get_missing_key_is_not_found() {
+        let tree = temporary_tree();
+        assert!(matches!(
+            get::<u64>(&tree, "missing").await,
+            Err(DatabaseError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_key() {
+        let tree = temporary_tree();
+        set(&tree, "key", 1u64).await.unwrap();
+        delete(&tree, "key").await.unwrap();
+        assert!(matches!(
+            get::<u64>(&tree, "key").await,
+            Err(DatabaseError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn set_many_applies_every_entry_atomically() {
+        let tree = temporary_tree();
+        set_many(
+            &tree,
+            vec![("a".to_string(), 1u64), ("b".to_string(), 2u64)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(get::<u64>(&tree, "a").await.unwrap(), 1);
+        assert_eq!(get::<u64>(&tree, "b").await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn delete_many_removes_every_key() {
+        let tree = temporary_tree();
+        set(&tree, "a", 1u64).await.unwrap();
+        set(&tree, "b", 2u64).await.unwrap();
+        delete_many(&tree, &["a", "b"]).await.unwrap();
+        assert!(get::<u64>(&tree, "a").await.is_err());
+        assert!(get::<u64>(&tree, "b").await.is_err());
+    }
+
+    #[test]
+    fn transact_commits_every_write_on_success() {
+        let tree = temporary_tree();
+        transact(&tree, |tx| {
+            tx.set("a", 1u64)?;
+            tx.set("b", 2u64)
+        })
+        .unwrap();
+
+        transact(&tree, |tx| {
+            assert_eq!(tx.get::<u64>("a").unwrap(), Some(1));
+            assert_eq!(tx.get::<u64>("b").unwrap(), Some(2));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn transact_rolls_back_every_write_on_error() {
+        let tree = temporary_tree();
+        let result = transact(&tree, |tx| {
+            tx.set("a", 1u64)?;
+            Err(DatabaseError::NotFound)
+        });
+        assert!(matches!(result, Err(DatabaseError::NotFound)));
+
+        transact(&tree, |tx| {
+            assert_eq!(tx.get::<u64>("a").unwrap(), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn transact2_commits_writes_to_both_trees_together() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree_a = db.open_tree("a").unwrap();
+        let tree_b = db.open_tree("b").unwrap();
+
+        transact2(&tree_a, &tree_b, |a, b| {
+            a.set("key", 1u64)?;
+            b.set("key", 2u64)
+        })
+        .unwrap();
+
+        transact(&tree_a, |tx| {
+            assert_eq!(tx.get::<u64>("key").unwrap(), Some(1));
+            Ok(())
+        })
+        .unwrap();
+        transact(&tree_b, |tx| {
+            assert_eq!(tx.get::<u64>("key").unwrap(), Some(2));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn transact2_rolls_back_both_trees_on_error() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree_a = db.open_tree("a").unwrap();
+        let tree_b = db.open_tree("b").unwrap();
+
+        let result = transact2(&tree_a, &tree_b, |a, b| {
+            a.set("key", 1u64)?;
+            b.set("key", 2u64)?;
+            Err(DatabaseError::NotFound)
+        });
+        assert!(matches!(result, Err(DatabaseError::NotFound)));
+
+        transact(&tree_a, |tx| {
+            assert_eq!(tx.get::<u64>("key").unwrap(), None);
+            Ok(())
+        })
+        .unwrap();
+        transact(&tree_b, |tx| {
+            assert_eq!(tx.get::<u64>("key").unwrap(), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::Mutex,
+};
+use web3::types::U256;
+
+use crate::{audit::log_sync, gateway::counters};
+
+/// Counters and gauges tracking poller health, exposed over HTTP in
+/// Prometheus text exposition format so operators can scrape gateway health
+/// and alert on stalled polling.
+#[derive(Default)]
+pub struct Metrics {
+    invoices_paid_total: AtomicU64,
+    invoices_expired_total: AtomicU64,
+    last_successful_poll_unix_seconds: AtomicU64,
+    settled_value_by_token: Mutex<HashMap<String, U256>>,
+    provider_errors_total: Mutex<HashMap<String, u64>>,
+}
+
+impl Metrics {
+    /// Records that a poll tick completed successfully at `unix_seconds`.
+    pub fn record_poll_success(&self, unix_seconds: u64) {
+        self.last_successful_poll_unix_seconds
+            .store(unix_seconds, Ordering::Relaxed);
+    }
+
+    /// Records an invoice paid in `token` (a token address, or `"native"`)
+    /// for `amount`.
+    pub async fn record_paid(&self, token: &str, amount: U256) {
+        self.invoices_paid_total.fetch_add(1, Ordering::Relaxed);
+        let mut settled = self.settled_value_by_token.lock().await;
+        *settled.entry(token.to_string()).or_insert_with(U256::zero) += amount;
+    }
+
+    /// Records an invoice swept as expired.
+    pub fn record_expired(&self) {
+        self.invoices_expired_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records an RPC error observed from `endpoint`.
+    pub async fn record_provider_error(&self, endpoint: &str) {
+        let mut errors = self.provider_errors_total.lock().await;
+        *errors.entry(endpoint.to_string()).or_insert(0) += 1;
+    }
+
+    /// Renders every metric in Prometheus text exposition format.
+    pub async fn render(&self, counters_tree: &sled::Tree) -> String {
+        let lifecycle = counters::read(counters_tree);
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP acceptevm_open_invoices Number of open invoices.");
+        let _ = writeln!(out, "# TYPE acceptevm_open_invoices gauge");
+        let _ = writeln!(out, "acceptevm_open_invoices {}", lifecycle.open);
+
+        let _ = writeln!(
+            out,
+            "# HELP acceptevm_invoices_paid_total Invoices paid since start."
+        );
+        let _ = writeln!(out, "# TYPE acceptevm_invoices_paid_total counter");
+        let _ = writeln!(
+            out,
+            "acceptevm_invoices_paid_total {}",
+            self.invoices_paid_total.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP acceptevm_invoices_expired_total Invoices expired since start."
+        );
+        let _ = writeln!(out, "# TYPE acceptevm_invoices_expired_total counter");
+        let _ = writeln!(
+            out,
+            "acceptevm_invoices_expired_total {}",
+            self.invoices_expired_total.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP acceptevm_last_successful_poll_unix_seconds Unix timestamp of the last successful poll."
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE acceptevm_last_successful_poll_unix_seconds gauge"
+        );
+        let _ = writeln!(
+            out,
+            "acceptevm_last_successful_poll_unix_seconds {}",
+            self.last_successful_poll_unix_seconds.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP acceptevm_settled_value_total Cumulative value settled, per token address (\"native\" for the gas token)."
+        );
+        let _ = writeln!(out, "# TYPE acceptevm_settled_value_total counter");
+        for (token, value) in self.settled_value_by_token.lock().await.iter() {
+            let _ = writeln!(
+                out,
+                "acceptevm_settled_value_total{{token=\"{}\"}} {}",
+                token, value
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP acceptevm_provider_errors_total RPC errors observed, per endpoint."
+        );
+        let _ = writeln!(out, "# TYPE acceptevm_provider_errors_total counter");
+        for (endpoint, count) in self.provider_errors_total.lock().await.iter() {
+            let _ = writeln!(
+                out,
+                "acceptevm_provider_errors_total{{endpoint=\"{}\"}} {}",
+                endpoint, count
+            );
+        }
+
+        out
+    }
+}
+
+/// Serves the metrics in Prometheus text exposition format on `bind_addr`
+/// until the process exits. Every request is answered with the metrics; its
+/// method and path are not checked.
+pub async fn serve_metrics(
+    bind_addr: SocketAddr,
+    metrics: std::sync::Arc<Metrics>,
+    counters_tree: sled::Tree,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(bind_addr).await?;
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        let counters_tree = counters_tree.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            if socket.read(&mut buf).await.is_err() {
+                return;
+            }
+            let body = metrics.render(&counters_tree).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            if let Err(error) = socket.write_all(response.as_bytes()).await {
+                log_sync(&format!("Failed to write metrics response: {}", error));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_counters_tree() -> sled::Tree {
+        sled::Config::new()
+            .temporary(true)
+            .open()
+            .unwrap()
+            .open_tree("counters")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn render_reports_paid_invoices_and_settled_value() {
+        let metrics = Metrics::default();
+        metrics.record_paid("native", U256::from(100)).await;
+        metrics.record_paid("native", U256::from(50)).await;
+
+        let body = metrics.render(&temporary_counters_tree()).await;
+
+        assert!(body.contains("acceptevm_invoices_paid_total 2"));
+        assert!(body.contains("acceptevm_settled_value_total{token=\"native\"} 150"));
+    }
+
+    #[tokio::test]
+    async fn render_reports_expired_invoices() {
+        let metrics = Metrics::default();
+        metrics.record_expired();
+        metrics.record_expired();
+
+        let body = metrics.render(&temporary_counters_tree()).await;
+
+        assert!(body.contains("acceptevm_invoices_expired_total 2"));
+    }
+
+    #[tokio::test]
+    async fn render_reports_provider_errors_per_endpoint() {
+        let metrics = Metrics::default();
+        metrics.record_provider_error("http://a").await;
+        metrics.record_provider_error("http://a").await;
+        metrics.record_provider_error("http://b").await;
+
+        let body = metrics.render(&temporary_counters_tree()).await;
+
+        assert!(body.contains("acceptevm_provider_errors_total{endpoint=\"http://a\"} 2"));
+        assert!(body.contains("acceptevm_provider_errors_total{endpoint=\"http://b\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn render_reports_open_invoices_from_counters_tree() {
+        let metrics = Metrics::default();
+        let counters_tree = temporary_counters_tree();
+        crate::gateway::db::transact(&counters_tree, crate::gateway::counters::apply_opened)
+            .unwrap();
+
+        let body = metrics.render(&counters_tree).await;
+
+        assert!(body.contains("acceptevm_open_invoices 1"));
+    }
+}
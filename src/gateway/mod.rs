@@ -0,0 +1,117 @@
+pub mod counters;
+pub mod db;
+pub mod metrics;
+pub mod provider;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use futures::future::BoxFuture;
+use tokio::sync::Mutex;
+use web3::types::U256;
+
+use crate::{
+    audit::log_sync,
+    common::DatabaseError,
+    gateway::{db, metrics::Metrics, provider::ProviderPool},
+    types::Invoice,
+};
+
+/// Invoked once an invoice has been detected as paid in full.
+pub type PaidCallback = Box<dyn FnMut(Invoice) -> BoxFuture<'static, ()> + Send>;
+
+/// Invoked once an invoice is swept past its deadline without being paid in
+/// full. The `U256` reports how much was actually received, if anything.
+pub type ExpiredCallback = Box<dyn FnMut(Invoice, U256) -> BoxFuture<'static, ()> + Send>;
+
+/// How the poller looks for incoming payments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectionMode {
+    /// Query the recipient's balance (`eth_getBalance` for native-coin
+    /// invoices, ERC20 `balanceOf` for token invoices) for every open invoice
+    /// on every tick.
+    BalancePolling,
+    /// Watch `Transfer` logs (ERC20) and block transactions (native coin)
+    /// incrementally from a persisted cursor instead of re-checking every
+    /// invoice's balance from scratch.
+    EventLog,
+}
+
+/// A running payment gateway: holds the node connection pool, the invoice
+/// store and the callbacks fired once an invoice is settled or expires.
+pub struct PaymentGateway {
+    pub providers: Arc<Mutex<ProviderPool>>,
+    pub tree: sled::Tree,
+    /// Tracks the last block number scanned by the event-log detection
+    /// backend, keyed per token address (or `"native"`).
+    pub log_cursor_tree: sled::Tree,
+    /// Aggregate open/paid/expired counters, see `gateway::counters`.
+    pub counters_tree: sled::Tree,
+    pub callback: Arc<Mutex<PaidCallback>>,
+    pub expired_callback: Arc<Mutex<ExpiredCallback>>,
+    pub poll_interval_seconds: u64,
+    pub detection_mode: DetectionMode,
+    /// Number of blocks a payment must be buried under before it is
+    /// considered final. Balances and logs are evaluated as of
+    /// `latest_block - required_confirmations`, so a reorg can't cause a
+    /// false-positive callback on a transient fork.
+    pub required_confirmations: u64,
+    /// Counters and gauges describing poller health, scraped over HTTP by
+    /// `spawn_metrics_server`.
+    pub metrics: Arc<Metrics>,
+}
+
+impl PaymentGateway {
+    pub fn new(
+        providers: ProviderPool,
+        tree: sled::Tree,
+        log_cursor_tree: sled::Tree,
+        counters_tree: sled::Tree,
+        callback: PaidCallback,
+        expired_callback: ExpiredCallback,
+        poll_interval_seconds: u64,
+        required_confirmations: u64,
+    ) -> Self {
+        let metrics = Arc::new(Metrics::default());
+        Self {
+            providers: Arc::new(Mutex::new(providers.with_metrics(metrics.clone()))),
+            tree,
+            log_cursor_tree,
+            counters_tree,
+            callback: Arc::new(Mutex::new(callback)),
+            expired_callback: Arc::new(Mutex::new(expired_callback)),
+            poll_interval_seconds,
+            detection_mode: DetectionMode::BalancePolling,
+            required_confirmations,
+            metrics,
+        }
+    }
+
+    /// Spawns the Prometheus metrics server on `bind_addr`, returning
+    /// immediately; the server runs for the lifetime of the returned task.
+    pub fn spawn_metrics_server(&self, bind_addr: SocketAddr) -> tokio::task::JoinHandle<()> {
+        let metrics = self.metrics.clone();
+        let counters_tree = self.counters_tree.clone();
+        tokio::spawn(async move {
+            if let Err(error) = metrics::serve_metrics(bind_addr, metrics, counters_tree).await {
+                log_sync(&format!("Metrics server stopped: {}", error));
+            }
+        })
+    }
+
+    /// Stores a new invoice under `key` and records it as open in the
+    /// aggregate counters, as a single atomic transaction spanning both
+    /// trees, so a crash between the two can never desync them.
+    pub async fn create_invoice(&self, key: &str, invoice: Invoice) -> Result<(), DatabaseError> {
+        db::transact2(&self.tree, &self.counters_tree, |invoices, counters| {
+            invoices.set(key, invoice.clone())?;
+            counters::apply_opened(counters)
+        })
+    }
+
+    /// Rebuilds the `open` counter from the invoice tree. Run this after an
+    /// unclean shutdown to recover from a crash that landed between an
+    /// invoice write and its counter update.
+    pub fn repair_counters(&self) -> Result<(), DatabaseError> {
+        counters::repair_counters(&self.tree, &self.counters_tree)
+    }
+}
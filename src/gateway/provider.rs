@@ -0,0 +1,320 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use thiserror::Error;
+use web3::{
+    transports::Http,
+    types::{Address, BlockNumber, Bytes, CallRequest, U256, U64},
+    Web3,
+};
+
+use crate::{audit::log_sync, gateway::metrics::Metrics};
+
+/// How long a single endpoint is given to answer before its response is
+/// treated as a failure. `web3::Http` has no built-in request timeout, so
+/// without this a single hanging node would block every read forever
+/// instead of just being demoted like one that errors outright.
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum ProviderError {
+    #[error("no endpoint reached the configured quorum")]
+    NoQuorum,
+    #[error("no healthy endpoints available")]
+    NoHealthyEndpoints,
+}
+
+/// The function selector for the ERC20 `balanceOf(address)` view.
+const BALANCE_OF_SELECTOR: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+
+fn pad_address(address: Address) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(address.as_bytes());
+    word
+}
+
+/// Returns the first value that at least `quorum` of `results` agree on, or
+/// `None` if no value reaches that threshold.
+fn quorum_value<T: PartialEq + Clone>(results: &[T], quorum: usize) -> Option<T> {
+    let mut tally: Vec<(T, usize)> = Vec::new();
+    for value in results {
+        match tally.iter_mut().find(|(seen, _)| seen == value) {
+            Some(entry) => entry.1 += 1,
+            None => tally.push((value.clone(), 1)),
+        }
+    }
+    tally
+        .into_iter()
+        .find(|(_, count)| *count >= quorum)
+        .map(|(value, _)| value)
+}
+
+struct Endpoint {
+    web3: Web3<Http>,
+    url: String,
+    demoted_until: Option<Instant>,
+}
+
+/// A pool of RPC endpoints dispatched to in parallel for every read, so a
+/// single flaky or lagging node can't cause a paid invoice to be silently
+/// missed or a callback to fire against stale state. A value is only
+/// returned once `quorum` endpoints agree on it; endpoints that error out
+/// are demoted and skipped for `cooldown`.
+pub struct ProviderPool {
+    endpoints: Vec<Endpoint>,
+    quorum: usize,
+    cooldown: Duration,
+    timeout: Duration,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl ProviderPool {
+    /// Builds a pool from a list of RPC URLs, requiring `quorum` of them to
+    /// agree before a read is considered trustworthy.
+    pub fn new(urls: &[String], quorum: usize, cooldown: Duration) -> Result<Self, web3::Error> {
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                let http = Http::new(url)?;
+                Ok(Endpoint {
+                    web3: Web3::new(http),
+                    url: url.clone(),
+                    demoted_until: None,
+                })
+            })
+            .collect::<Result<Vec<_>, web3::Error>>()?;
+        Ok(Self {
+            endpoints,
+            quorum: quorum.max(1),
+            cooldown,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            metrics: None,
+        })
+    }
+
+    /// A single-endpoint pool for callers that don't need failover, e.g.
+    /// tests or a gateway configured with only one RPC URL.
+    pub fn single(web3: Web3<Http>) -> Self {
+        Self {
+            endpoints: vec![Endpoint {
+                web3,
+                url: "default".to_string(),
+                demoted_until: None,
+            }],
+            quorum: 1,
+            cooldown: Duration::from_secs(30),
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            metrics: None,
+        }
+    }
+
+    /// Overrides the per-endpoint request timeout (10 seconds by default).
+    /// An endpoint that doesn't answer within this window is demoted for
+    /// `cooldown`, the same as one that returns an error.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reports per-endpoint errors to `metrics` going forward.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Runs `f` against the first endpoint not currently in its cooldown
+    /// window, for reads (log streaming, full blocks) that aren't cheap or
+    /// meaningful to compare for quorum agreement. A failing or timed-out
+    /// call still demotes the endpoint and counts against its
+    /// `provider_errors_total` metric, the same as a `poll`-backed read, so
+    /// the event-log detection path isn't a blind spot for RPC health.
+    pub async fn any_call<T, F, Fut>(&mut self, f: F) -> Result<T, ProviderError>
+    where
+        F: FnOnce(Web3<Http>) -> Fut,
+        Fut: std::future::Future<Output = Result<T, web3::Error>>,
+    {
+        let now = Instant::now();
+        let index = self
+            .endpoints
+            .iter()
+            .position(|endpoint| {
+                !endpoint
+                    .demoted_until
+                    .map(|until| until > now)
+                    .unwrap_or(false)
+            })
+            .ok_or(ProviderError::NoHealthyEndpoints)?;
+
+        let web3 = self.endpoints[index].web3.clone();
+        let timeout = self.timeout;
+        let cooldown = self.cooldown;
+        let metrics = self.metrics.clone();
+        let result = tokio::time::timeout(timeout, f(web3)).await;
+
+        let endpoint = &mut self.endpoints[index];
+        match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(error)) => {
+                log_sync(&format!("Provider {} failed: {}", endpoint.url, error));
+                if let Some(metrics) = &metrics {
+                    metrics.record_provider_error(&endpoint.url).await;
+                }
+                endpoint.demoted_until = Some(now + cooldown);
+                Err(ProviderError::NoHealthyEndpoints)
+            }
+            Err(_elapsed) => {
+                log_sync(&format!(
+                    "Provider {} timed out after {:?}",
+                    endpoint.url, timeout
+                ));
+                if let Some(metrics) = &metrics {
+                    metrics.record_provider_error(&endpoint.url).await;
+                }
+                endpoint.demoted_until = Some(now + cooldown);
+                Err(ProviderError::NoHealthyEndpoints)
+            }
+        }
+    }
+
+    /// Dispatches `f` to every non-demoted endpoint concurrently and returns
+    /// a value once `quorum` of them agree; endpoints that error, or that
+    /// don't answer within `timeout`, are demoted for `cooldown`. Dispatch is
+    /// concurrent so one slow node can't add its latency to every other
+    /// node's read.
+    async fn poll<T, F, Fut>(&mut self, f: F) -> Result<T, ProviderError>
+    where
+        T: PartialEq + Clone,
+        F: Fn(Web3<Http>) -> Fut,
+        Fut: std::future::Future<Output = Result<T, web3::Error>>,
+    {
+        let now = Instant::now();
+        let cooldown = self.cooldown;
+        let timeout = self.timeout;
+        let metrics = self.metrics.clone();
+
+        let live: Vec<usize> = self
+            .endpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, endpoint)| {
+                !endpoint
+                    .demoted_until
+                    .map(|until| until > now)
+                    .unwrap_or(false)
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        let f = &f;
+        let calls = live.iter().map(|&index| {
+            let web3 = self.endpoints[index].web3.clone();
+            async move { tokio::time::timeout(timeout, f(web3)).await }
+        });
+        let results = join_all(calls).await;
+
+        let mut values: Vec<T> = Vec::new();
+        for (index, result) in live.into_iter().zip(results) {
+            let endpoint = &mut self.endpoints[index];
+            match result {
+                Ok(Ok(value)) => values.push(value),
+                Ok(Err(error)) => {
+                    log_sync(&format!("Provider {} failed: {}", endpoint.url, error));
+                    if let Some(metrics) = &metrics {
+                        metrics.record_provider_error(&endpoint.url).await;
+                    }
+                    endpoint.demoted_until = Some(now + cooldown);
+                }
+                Err(_elapsed) => {
+                    log_sync(&format!(
+                        "Provider {} timed out after {:?}",
+                        endpoint.url, timeout
+                    ));
+                    if let Some(metrics) = &metrics {
+                        metrics.record_provider_error(&endpoint.url).await;
+                    }
+                    endpoint.demoted_until = Some(now + cooldown);
+                }
+            }
+        }
+        if values.is_empty() {
+            return Err(ProviderError::NoHealthyEndpoints);
+        }
+        quorum_value(&values, self.quorum).ok_or(ProviderError::NoQuorum)
+    }
+
+    /// Native-coin balance of `address`, agreed on by quorum.
+    pub async fn native_balance(
+        &mut self,
+        address: Address,
+        block: Option<BlockNumber>,
+    ) -> Result<U256, ProviderError> {
+        self.poll(|web3| async move { web3.eth().balance(address, block).await })
+            .await
+    }
+
+    /// ERC20 `balanceOf(owner)` on `token`, agreed on by quorum.
+    pub async fn token_balance(
+        &mut self,
+        token: Address,
+        owner: Address,
+        block: Option<BlockNumber>,
+    ) -> Result<U256, ProviderError> {
+        let mut data = BALANCE_OF_SELECTOR.to_vec();
+        data.extend_from_slice(&pad_address(owner));
+        let call = CallRequest {
+            to: Some(token),
+            data: Some(Bytes(data)),
+            ..Default::default()
+        };
+        self.poll(|web3| {
+            let call = call.clone();
+            async move {
+                web3.eth()
+                    .call(call, block)
+                    .await
+                    .map(|bytes| U256::from_big_endian(&bytes.0))
+            }
+        })
+        .await
+    }
+
+    /// Latest block height, agreed on by quorum.
+    pub async fn block_number(&mut self) -> Result<U64, ProviderError> {
+        self.poll(|web3| async move { web3.eth().block_number().await })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorum_value_picks_the_value_reaching_quorum() {
+        let results = vec![1, 2, 1, 1, 2];
+        assert_eq!(quorum_value(&results, 3), Some(1));
+    }
+
+    #[test]
+    fn quorum_value_none_when_no_value_reaches_quorum() {
+        let results = vec![1, 2, 3];
+        assert_eq!(quorum_value(&results, 2), None);
+    }
+
+    #[test]
+    fn quorum_value_empty_results_is_none() {
+        let results: Vec<u64> = Vec::new();
+        assert_eq!(quorum_value(&results, 1), None);
+    }
+
+    #[test]
+    fn pad_address_left_pads_to_32_bytes() {
+        let address = Address::from_low_u64_be(0x1234);
+        let word = pad_address(address);
+        assert_eq!(&word[..12], &[0u8; 12]);
+        assert_eq!(&word[12..], address.as_bytes());
+    }
+}
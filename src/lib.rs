@@ -0,0 +1,6 @@
+pub mod audit;
+pub mod common;
+pub mod erc20;
+pub mod gateway;
+pub mod poller;
+pub mod types;
@@ -1,86 +1,406 @@
-use web3::{transports::Http, types::U256, Web3};
+use web3::types::{Address, BlockId, BlockNumber, FilterBuilder, H256, U256};
 
 use crate::{
     audit::logger::log_sync,
+    common::get_unix_time_seconds,
     erc20::ERC20Token,
     gateway::{
-        db::{delete, get_all},
-        PaymentGateway,
+        counters,
+        db::{self, get, get_all},
+        provider::{ProviderError, ProviderPool},
+        DetectionMode, PaymentGateway,
     },
     types::Invoice,
 };
 
-async fn check_if_token_received(
-    token: ERC20Token,
-    invoice: Invoice,
-) -> Result<bool, web3::contract::Error> {
-    let balance_of_recipient = token.get_balance(invoice.to).await?;
-    if balance_of_recipient.ge(&invoice.amount) {
-        return Ok(true);
+/// keccak256("Transfer(address,address,uint256)"), the topic0 every ERC20
+/// `Transfer` log is indexed under.
+const TRANSFER_TOPIC: H256 = H256([
+    0xdd, 0xf2, 0x52, 0xad, 0x1b, 0xe2, 0xc8, 0x9b, 0x69, 0xc2, 0xb0, 0x68, 0xfc, 0x37, 0x8d, 0xaa,
+    0x95, 0x2b, 0xa7, 0xf1, 0x63, 0xc4, 0xa1, 0x16, 0x28, 0xf5, 0x5a, 0x4d, 0xf5, 0x23, 0xb3, 0xef,
+]);
+
+/// Left-pads an address into the 32-byte topic encoding used for indexed
+/// event arguments.
+fn address_topic(address: Address) -> H256 {
+    let mut bytes = [0u8; 32];
+    bytes[12..].copy_from_slice(address.as_bytes());
+    H256::from(bytes)
+}
+
+/// The sled keys an invoice's log-scan cursor and running total are kept
+/// under, scoped to the invoice's own key so that two invoices paid to the
+/// same recipient address (a reused merchant address, or a new invoice
+/// issued after a prior one on that address settled) can never see each
+/// other's scan history.
+fn token_log_keys(invoice_key: &str) -> (String, String) {
+    (
+        format!("token_block:{}", invoice_key),
+        format!("token_received:{}", invoice_key),
+    )
+}
+
+/// Same as `token_log_keys`, for the native-coin detection path.
+fn native_log_keys(invoice_key: &str) -> (String, String) {
+    (
+        format!("native_block:{}", invoice_key),
+        format!("native_received:{}", invoice_key),
+    )
+}
+
+/// The keys an invoice's log-scan state is stored under, for whichever
+/// detection path applies to it.
+fn log_keys(invoice_key: &str, token_address: Option<Address>) -> (String, String) {
+    match token_address {
+        Some(_) => token_log_keys(invoice_key),
+        None => native_log_keys(invoice_key),
+    }
+}
+
+/// The inclusive block range still left to scan given the last block a
+/// cursor recorded (`None` if nothing has been scanned yet) and `confirmed`
+/// (the chain head minus the configured confirmation depth). Returns `None`
+/// once the cursor has caught up to `confirmed`, so a tick with nothing new
+/// to scan never issues an RPC call at all.
+fn scan_range(last_scanned: Option<u64>, confirmed: u64) -> Option<(u64, u64)> {
+    let from_block = last_scanned.map(|last| last + 1).unwrap_or(confirmed);
+    if from_block > confirmed {
+        None
+    } else {
+        Some((from_block, confirmed))
     }
-    Ok(false)
 }
 
-/// Retrieves the gas token balance of the specified address on the specified web3 instance
-async fn get_native_balance(web3: Web3<Http>, address: String) -> Result<U256, web3::Error> {
-    web3.eth().balance(address.parse().unwrap(), None).await
+/// Retrieves the gas token balance of the specified address as of `block`,
+/// agreed on by quorum.
+async fn get_native_balance(
+    providers: &mut ProviderPool,
+    address: String,
+    block: Option<BlockNumber>,
+) -> Result<U256, ProviderError> {
+    let address: Address = address.parse().map_err(|_| ProviderError::NoHealthyEndpoints)?;
+    providers.native_balance(address, block).await
 }
 
-/// Used to check if the invoice recipient has received enough money to cover the invoice
-async fn check_if_native_received(web3: Web3<Http>, invoice: Invoice) -> Result<bool, web3::Error> {
-    let balance_of_recipient = get_native_balance(web3, invoice.to).await?;
-    if balance_of_recipient.ge(&invoice.amount) {
-        return Ok(true);
+/// Returns the block a payment must be at or behind to be considered final,
+/// i.e. `latest_block - required_confirmations`.
+async fn confirmed_block_number(
+    providers: &mut ProviderPool,
+    required_confirmations: u64,
+) -> Result<u64, ProviderError> {
+    let latest = providers.block_number().await?;
+    Ok(latest.as_u64().saturating_sub(required_confirmations))
+}
+
+/// Scans `Transfer` logs emitted by `token_address` to `recipient` since the
+/// last persisted cursor for `invoice_key`, up to `confirmed` (the chain
+/// head minus the configured confirmation depth), and returns the
+/// cumulative amount received so far. A provider error leaves the cursor
+/// untouched and falls back to the last persisted total rather than
+/// reporting nothing received.
+async fn scan_token_transfers_received(
+    providers: &mut ProviderPool,
+    cursor_tree: &sled::Tree,
+    invoice_key: &str,
+    token_address: Address,
+    recipient: Address,
+    confirmed: u64,
+) -> U256 {
+    let (cursor_key, received_key) = token_log_keys(invoice_key);
+
+    let last_scanned = get::<u64>(cursor_tree, &cursor_key).await.ok();
+    let mut received = get::<U256>(cursor_tree, &received_key)
+        .await
+        .unwrap_or_else(|_| U256::zero());
+
+    let Some((from_block, to_block)) = scan_range(last_scanned, confirmed) else {
+        return received;
+    };
+
+    let filter = FilterBuilder::default()
+        .address(vec![token_address])
+        .topics(
+            Some(vec![TRANSFER_TOPIC]),
+            None,
+            Some(vec![address_topic(recipient)]),
+            None,
+        )
+        .from_block(BlockNumber::Number(from_block.into()))
+        .to_block(BlockNumber::Number(to_block.into()))
+        .build();
+
+    let logs = match providers
+        .any_call(|web3| async move { web3.eth().logs(filter).await })
+        .await
+    {
+        Ok(logs) => logs,
+        Err(error) => {
+            log_sync(&format!(
+                "Failed to scan token transfer logs, using last known total: {}",
+                error
+            ));
+            return received;
+        }
+    };
+    for log in logs {
+        received += U256::from_big_endian(&log.data.0);
+    }
+
+    if let Err(error) = db::transact(cursor_tree, |tx| {
+        tx.set(&received_key, received)?;
+        tx.set(&cursor_key, to_block)
+    }) {
+        log_sync(&format!("Could not persist log scan cursor: {}", error));
     }
-    Ok(false)
+
+    received
+}
+
+/// Scans the transactions of every new block since the last persisted
+/// cursor for `invoice_key`, up to `confirmed` (the chain head minus the
+/// configured confirmation depth), for transfers to `recipient`, and
+/// returns the cumulative native-coin amount received so far. A provider
+/// error leaves the cursor untouched and falls back to the last persisted
+/// total rather than reporting nothing received.
+async fn scan_native_transfers_received(
+    providers: &mut ProviderPool,
+    cursor_tree: &sled::Tree,
+    invoice_key: &str,
+    recipient: Address,
+    confirmed: u64,
+) -> U256 {
+    let (cursor_key, received_key) = native_log_keys(invoice_key);
+
+    let last_scanned = get::<u64>(cursor_tree, &cursor_key).await.ok();
+    let mut received = get::<U256>(cursor_tree, &received_key)
+        .await
+        .unwrap_or_else(|_| U256::zero());
+
+    let Some((from_block, to_block)) = scan_range(last_scanned, confirmed) else {
+        return received;
+    };
+
+    let persisted = received;
+    let mut block_number = from_block;
+    while block_number <= to_block {
+        let result = providers
+            .any_call(|web3| async move {
+                web3.eth()
+                    .block_with_txs(BlockId::Number(BlockNumber::Number(block_number.into())))
+                    .await
+            })
+            .await;
+        match result {
+            Ok(Some(block)) => {
+                for tx in block.transactions {
+                    if tx.to == Some(recipient) {
+                        received += tx.value;
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(error) => {
+                log_sync(&format!(
+                    "Failed to scan native transfers, using last known total: {}",
+                    error
+                ));
+                return persisted;
+            }
+        }
+        block_number += 1;
+    }
+
+    if let Err(error) = db::transact(cursor_tree, |tx| {
+        tx.set(&received_key, received)?;
+        tx.set(&cursor_key, to_block)
+    }) {
+        log_sync(&format!("Could not persist log scan cursor: {}", error));
+    }
+
+    received
 }
 
 /// A function that branches control flow depending on the invoice shall
-/// be paid by an ERC20-compatible token or the native gas token on the network
-async fn check_and_process(web3: Web3<Http>, invoice: Invoice) -> bool {
-    match invoice.clone().method.token_address {
-        Some(address) => {
-            let token = ERC20Token::new(web3, address);
-            match check_if_token_received(token, invoice).await {
-                Ok(result) => result,
+/// be paid by an ERC20-compatible token or the native gas token on the
+/// network, returning the amount received towards it so far. `confirmed` is
+/// the chain head minus the configured confirmation depth, looked up once
+/// per poll tick by the caller rather than once per invoice. `invoice_key`
+/// scopes the event-log scan cursor to this invoice, so a recipient address
+/// reused across invoices can't inherit a prior invoice's scan history.
+async fn received_amount(
+    gateway: &PaymentGateway,
+    invoice_key: &str,
+    invoice: Invoice,
+    confirmed: u64,
+) -> U256 {
+    let mut providers = gateway.providers.lock().await;
+    match (gateway.detection_mode, invoice.clone().method.token_address) {
+        (DetectionMode::BalancePolling, Some(address)) => {
+            let token = ERC20Token::new(address);
+            match token
+                .get_balance(
+                    &mut providers,
+                    invoice.to,
+                    Some(BlockNumber::Number(confirmed.into())),
+                )
+                .await
+            {
+                Ok(balance) => balance,
                 Err(error) => {
                     log_sync(&format!("Failed to check balance: {}", error));
-                    false
+                    U256::zero()
                 }
             }
         }
-        None => match check_if_native_received(web3, invoice).await {
-            Ok(result) => result,
-            Err(error) => {
-                log_sync(&format!("Failed to check balance: {}", error));
-                false
+        (DetectionMode::BalancePolling, None) => {
+            match get_native_balance(
+                &mut providers,
+                invoice.to,
+                Some(BlockNumber::Number(confirmed.into())),
+            )
+            .await
+            {
+                Ok(balance) => balance,
+                Err(error) => {
+                    log_sync(&format!("Failed to check balance: {}", error));
+                    U256::zero()
+                }
             }
-        },
+        }
+        (DetectionMode::EventLog, Some(address)) => {
+            let recipient = match invoice.to.parse::<Address>() {
+                Ok(recipient) => recipient,
+                Err(error) => {
+                    log_sync(&format!("Invalid invoice recipient: {}", error));
+                    return U256::zero();
+                }
+            };
+            scan_token_transfers_received(
+                &mut providers,
+                &gateway.log_cursor_tree,
+                invoice_key,
+                address,
+                recipient,
+                confirmed,
+            )
+            .await
+        }
+        (DetectionMode::EventLog, None) => {
+            let recipient = match invoice.to.parse::<Address>() {
+                Ok(recipient) => recipient,
+                Err(error) => {
+                    log_sync(&format!("Invalid invoice recipient: {}", error));
+                    return U256::zero();
+                }
+            };
+            scan_native_transfers_received(
+                &mut providers,
+                &gateway.log_cursor_tree,
+                invoice_key,
+                recipient,
+                confirmed,
+            )
+            .await
+        }
+    }
+}
+
+/// Drops the event-log scan cursor/received-total kept for a settled
+/// invoice, so `log_cursor_tree` doesn't accumulate an entry per invoice
+/// forever. A no-op, harmless to call unconditionally, for invoices
+/// detected via `DetectionMode::BalancePolling` which never wrote one.
+async fn forget_log_scan_state(gateway: &PaymentGateway, invoice_key: &str, invoice: &Invoice) {
+    let (cursor_key, received_key) = log_keys(invoice_key, invoice.method.token_address);
+    if let Err(error) = db::delete_many(
+        &gateway.log_cursor_tree,
+        &[cursor_key.as_str(), received_key.as_str()],
+    )
+    .await
+    {
+        log_sync(&format!("Could not clear log scan state: {}", error));
     }
 }
 
-/// Periodically checks if invoices are paid in accordance
-/// to the specified polling interval.
+/// Periodically checks if invoices are paid, and sweeps invoices that have
+/// passed their deadline without being paid in full, in accordance to the
+/// specified polling interval.
 pub async fn poll_payments(gateway: PaymentGateway) {
     loop {
         match get_all::<Invoice>(&gateway.tree).await {
             Ok(all) => {
-                for entry in all {
-                    let check_result =
-                        check_and_process(gateway.web3.clone(), entry.clone().1).await;
-                    if check_result {
-                        match delete(&gateway.tree, &entry.0).await {
-                            Ok(()) => {
-                                let mut lock = gateway.callback.lock().await;
-                                (&mut *lock)(entry.1).await;
+                let confirmed = {
+                    let mut providers = gateway.providers.lock().await;
+                    confirmed_block_number(&mut providers, gateway.required_confirmations).await
+                };
+                match confirmed {
+                    Ok(confirmed) => {
+                        for (key, invoice) in all {
+                            let received =
+                                received_amount(&gateway, &key, invoice.clone(), confirmed).await;
+                            if received.ge(&invoice.amount) {
+                                match db::transact2(
+                                    &gateway.tree,
+                                    &gateway.counters_tree,
+                                    |invoices, counters| {
+                                        invoices.delete(&key)?;
+                                        counters::apply_paid(counters, invoice.amount)
+                                    },
+                                ) {
+                                    Ok(()) => {
+                                        forget_log_scan_state(&gateway, &key, &invoice).await;
+                                        let token_key = invoice
+                                            .method
+                                            .token_address
+                                            .map(|address| format!("{:?}", address))
+                                            .unwrap_or_else(|| "native".to_string());
+                                        gateway
+                                            .metrics
+                                            .record_paid(&token_key, invoice.amount)
+                                            .await;
+                                        let mut lock = gateway.callback.lock().await;
+                                        (&mut *lock)(invoice).await;
+                                    }
+                                    Err(error) => {
+                                        log_sync(&format!(
+                                            "Could not remove paid invoice and update counters, did not callback: {}",
+                                            error
+                                        ));
+                                    }
+                                }
+                                continue;
                             }
-                            Err(error) => {
-                                log_sync(&format!(
-                                    "Could not remove paid invoice, did not callback: {}",
-                                    error
-                                ));
+
+                            if get_unix_time_seconds() >= invoice.expires_at {
+                                match db::transact2(
+                                    &gateway.tree,
+                                    &gateway.counters_tree,
+                                    |invoices, counters| {
+                                        invoices.delete(&key)?;
+                                        counters::apply_expired(counters)
+                                    },
+                                ) {
+                                    Ok(()) => {
+                                        forget_log_scan_state(&gateway, &key, &invoice).await;
+                                        gateway.metrics.record_expired();
+                                        let mut lock = gateway.expired_callback.lock().await;
+                                        (&mut *lock)(invoice, received).await;
+                                    }
+                                    Err(error) => {
+                                        log_sync(&format!(
+                                            "Could not remove expired invoice and update counters, did not callback: {}",
+                                            error
+                                        ));
+                                    }
+                                }
                             }
                         }
+                        gateway.metrics.record_poll_success(get_unix_time_seconds());
+                    }
+                    Err(error) => {
+                        log_sync(&format!(
+                            "Could not read confirmed block, skipping poll tick: {}",
+                            error
+                        ));
                     }
                 }
             }
@@ -100,21 +420,94 @@ pub async fn poll_payments(gateway: PaymentGateway) {
 
 #[cfg(test)]
 mod tests {
-    use web3::{transports::Http, types::U256, Web3};
+    use super::*;
 
-    use crate::poller::get_native_balance;
+    #[test]
+    fn scan_range_starts_at_confirmed_with_no_prior_cursor() {
+        assert_eq!(scan_range(None, 100), Some((100, 100)));
+    }
+
+    #[test]
+    fn scan_range_resumes_one_block_past_the_last_scanned() {
+        assert_eq!(scan_range(Some(90), 100), Some((91, 100)));
+    }
+
+    #[test]
+    fn scan_range_excludes_blocks_already_scanned() {
+        assert_eq!(scan_range(Some(100), 100), None);
+    }
+
+    #[test]
+    fn scan_range_excludes_blocks_past_the_confirmation_depth() {
+        // `confirmed` behind the last scanned block (e.g. `required_confirmations`
+        // was raised): nothing newly confirmed to scan.
+        assert_eq!(scan_range(Some(105), 100), None);
+    }
+
+    #[test]
+    fn token_and_native_log_keys_are_scoped_to_the_invoice() {
+        let (a_cursor, a_received) = token_log_keys("invoice-a");
+        let (b_cursor, b_received) = token_log_keys("invoice-b");
+        assert_ne!(a_cursor, b_cursor);
+        assert_ne!(a_received, b_received);
+
+        let (native_cursor, native_received) = native_log_keys("invoice-a");
+        assert_ne!(a_cursor, native_cursor);
+        assert_ne!(a_received, native_received);
+    }
+
+    #[test]
+    fn log_keys_dispatches_on_token_address() {
+        let token = Address::from_low_u64_be(1);
+        let (token_cursor, _) = log_keys("invoice", Some(token));
+        let (native_cursor, _) = log_keys("invoice", None);
+        assert_ne!(token_cursor, native_cursor);
+    }
 
     #[tokio::test]
-    async fn valid_balance() {
-        let http = Http::new("https://bsc-dataseed1.binance.org/").unwrap();
-        let web3 = Web3::new(http);
-        let balance = get_native_balance(
-            web3,
-            "0x2170ed0880ac9a755fd29b2688956bd959f933f8".to_string(),
-        )
-        .await
+    async fn cursor_round_trip_resumes_from_the_persisted_block() {
+        let tree = sled::Config::new()
+            .temporary(true)
+            .open()
+            .unwrap()
+            .open_tree("log_cursor")
+            .unwrap();
+        let (cursor_key, received_key) = token_log_keys("invoice-1");
+
+        db::transact(&tree, |tx| {
+            tx.set(&received_key, U256::from(42))?;
+            tx.set(&cursor_key, 100u64)
+        })
+        .unwrap();
+
+        let last_scanned = get::<u64>(&tree, &cursor_key).await.ok();
+        let received = get::<U256>(&tree, &received_key).await.unwrap();
+
+        assert_eq!(received, U256::from(42));
+        assert_eq!(scan_range(last_scanned, 100), None);
+        assert_eq!(scan_range(last_scanned, 150), Some((101, 150)));
+    }
+
+    #[tokio::test]
+    async fn delete_many_clears_both_native_log_keys_for_the_invoice() {
+        let tree = sled::Config::new()
+            .temporary(true)
+            .open()
+            .unwrap()
+            .open_tree("log_cursor")
+            .unwrap();
+        let (cursor_key, received_key) = native_log_keys("invoice-1");
+        db::transact(&tree, |tx| {
+            tx.set(&received_key, U256::from(7))?;
+            tx.set(&cursor_key, 100u64)
+        })
         .unwrap();
-        println!("Balance check: {}", balance);
-        assert!(balance.ge(&U256::zero()));
+
+        db::delete_many(&tree, &[cursor_key.as_str(), received_key.as_str()])
+            .await
+            .unwrap();
+
+        assert!(get::<u64>(&tree, &cursor_key).await.is_err());
+        assert!(get::<U256>(&tree, &received_key).await.is_err());
     }
 }
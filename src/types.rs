@@ -0,0 +1,43 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use web3::types::{Address, U256};
+
+use crate::common::DatabaseError;
+
+/// Implemented by every type that is persisted in a sled tree.
+pub trait Serializable: Sized {
+    fn to_bin(&self) -> Result<Vec<u8>, DatabaseError>;
+    fn from_bin(bin: Vec<u8>) -> Result<Self, DatabaseError>;
+}
+
+impl<T> Serializable for T
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn to_bin(&self) -> Result<Vec<u8>, DatabaseError> {
+        bincode::serialize(self).map_err(|_| DatabaseError::Serialize)
+    }
+
+    fn from_bin(bin: Vec<u8>) -> Result<Self, DatabaseError> {
+        bincode::deserialize(&bin).map_err(|_| DatabaseError::Deserialize)
+    }
+}
+
+/// Describes which asset an invoice must be settled in: the chain's native
+/// coin when `token_address` is `None`, or the given ERC20 token otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentMethod {
+    pub token_address: Option<Address>,
+}
+
+/// A single outstanding payment request tracked by the gateway.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invoice {
+    pub to: String,
+    pub amount: U256,
+    pub method: PaymentMethod,
+    /// Unix timestamp (seconds) the invoice was created at.
+    pub created_at: u64,
+    /// Unix timestamp (seconds) after which the invoice is swept as expired
+    /// if it has not been paid in full.
+    pub expires_at: u64,
+}